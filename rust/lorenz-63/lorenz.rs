@@ -1,28 +1,412 @@
-// Lorenz 63 model
+// Lorenz 63 model, generalized into a reusable dynamical-systems core
 
-fn main() {
-    const RHO: f64 = 10.0;  // Prandtl number
-    const SIGMA: f64 = 8.0 / 3.0;  // Rayleigh number
-    const BETA: f64 = 28.0;
+use rayon::prelude::*;
+use std::io::{self, Write};
+use std::ops::{Add, Sub, Mul};
 
-    let (mut x, mut y, mut z) = (1.0, 1.0, 1.0);  // position
-    let (mut dx, mut dy, mut dz);  // changes in position
-    let dt = 1e-3;  // time step
+/// A point in 3-space, used both as a position/velocity and as a derivative.
+#[derive(Clone, Copy, Debug)]
+struct Vec3 {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Vec3 {
+    const ZERO: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
+
+    fn norm(self) -> f64 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    /// Component-wise (Hadamard) product, used to scale noise per coordinate.
+    fn hadamard(self, other: Vec3) -> Vec3 {
+        Vec3 { x: self.x * other.x, y: self.y * other.y, z: self.z * other.z }
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, rhs: Vec3) -> Vec3 {
+        Vec3 { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z }
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, rhs: Vec3) -> Vec3 {
+        Vec3 { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
+    }
+}
+
+impl Mul<f64> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, rhs: f64) -> Vec3 {
+        Vec3 { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
+    }
+}
+
+/// The state (or derivative) of a system made of one or more `Vec3`s.
+type Vec3s = Vec<Vec3>;
+
+fn add_all(a: &[Vec3], b: &[Vec3]) -> Vec3s {
+    a.iter().zip(b).map(|(&u, &v)| u + v).collect()
+}
+
+fn sub_all(a: &[Vec3], b: &[Vec3]) -> Vec3s {
+    a.iter().zip(b).map(|(&u, &v)| u - v).collect()
+}
 
-    println!("Lorenz 63 model");
-    println!("Prandtl number = {:?}", RHO);
-    println!("Rayleigh number = {:.8}", SIGMA);
-    println!("beta = {:?}", BETA);
-    println!("The initial state is at [{:?}, {:?}, {:?}]", x, y, z);
+fn scale_all(a: &[Vec3], s: f64) -> Vec3s {
+    a.iter().map(|&u| u * s).collect()
+}
+
+/// Euclidean norm of the state/derivative, treating every coordinate of
+/// every `Vec3` as one flat vector.
+fn norm_all(a: &[Vec3]) -> f64 {
+    a.iter().map(|v| v.x * v.x + v.y * v.y + v.z * v.z).sum::<f64>().sqrt()
+}
 
-    // forward Euler scheme
+/// A system whose state evolves according to a derivative computed from the
+/// current state, independent of time. `Sync` so a single field can be
+/// shared across the threads driving an ensemble.
+trait VectorField: Sync {
+    fn derivative(&self, state: &[Vec3]) -> Vec3s;
+}
+
+/// The Lorenz 63 system, with state `[position]`.
+struct Lorenz63 {
+    rho: f64,   // Rayleigh number
+    sigma: f64, // Prandtl number
+    beta: f64,
+}
+
+impl VectorField for Lorenz63 {
+    fn derivative(&self, state: &[Vec3]) -> Vec3s {
+        let p = state[0];
+        vec![Vec3 {
+            x: self.sigma * (p.y - p.x),
+            y: p.x * (self.rho - p.z) - p.y,
+            z: p.x * p.y - self.beta * p.z,
+        }]
+    }
+}
+
+/// Constant per-coordinate noise amplitude, used to drive a stochastic
+/// Lorenz 63 variant via `step_euler_maruyama`.
+struct ConstantNoise {
+    amplitude: f64,
+}
+
+impl VectorField for ConstantNoise {
+    fn derivative(&self, state: &[Vec3]) -> Vec3s {
+        vec![Vec3 { x: self.amplitude, y: self.amplitude, z: self.amplitude }; state.len()]
+    }
+}
+
+/// An N-body system of point masses interacting under Newtonian gravity,
+/// with state `[position_0..N, velocity_0..N]`. Second-order (position and
+/// velocity both evolve), unlike `Lorenz63`, so that the integrator actually
+/// models inertia instead of `dposition/dt = acceleration`.
+struct NBody {
+    g: f64,
+    masses: Vec<f64>,
+}
+
+impl VectorField for NBody {
+    fn derivative(&self, state: &[Vec3]) -> Vec3s {
+        let n = self.masses.len();
+        let positions = &state[..n];
+        let velocities = &state[n..];
+
+        let mut acceleration = vec![Vec3::ZERO; n];
+        for i in 0..n {
+            for j in 0..n {
+                if i == j {
+                    continue;
+                }
+                let r = positions[j] - positions[i];
+                let distance = r.norm();
+                acceleration[i] = acceleration[i] + r * (self.g * self.masses[j] / distance.powi(3));
+            }
+        }
+
+        let mut derivative = velocities.to_vec();
+        derivative.extend(acceleration);
+        derivative
+    }
+}
+
+/// Numerical integration scheme used to advance the state by one step.
+enum Scheme {
+    /// First-order forward Euler.
+    Euler,
+    /// Classical fourth-order Runge-Kutta.
+    Rk4,
+}
+
+/// Advance `y` by one step of size `dt` under `field`, using `scheme`.
+fn integrate(field: &dyn VectorField, y: &[Vec3], dt: f64, scheme: &Scheme) -> Vec3s {
+    match scheme {
+        Scheme::Euler => add_all(y, &scale_all(&field.derivative(y), dt)),
+        Scheme::Rk4 => {
+            let k1 = field.derivative(y);
+            let k2 = field.derivative(&add_all(y, &scale_all(&k1, dt / 2.0)));
+            let k3 = field.derivative(&add_all(y, &scale_all(&k2, dt / 2.0)));
+            let k4 = field.derivative(&add_all(y, &scale_all(&k3, dt)));
+            let sum = add_all(&add_all(&k1, &scale_all(&k2, 2.0)), &add_all(&scale_all(&k3, 2.0), &k4));
+            add_all(y, &scale_all(&sum, dt / 6.0))
+        }
+    }
+}
+
+/// Advance every member of an ensemble by one step, in parallel across
+/// trajectories so that large ensembles scale across cores.
+fn integrate_ensemble(field: &dyn VectorField, states: &mut [Vec3s], dt: f64, scheme: &Scheme) {
+    states.par_iter_mut().for_each(|state| {
+        *state = integrate(field, state, dt, scheme);
+    });
+}
+
+/// Separation at which the shadow trajectory is kept from the reference.
+const LYAPUNOV_D0: f64 = 1e-9;
+
+/// Estimate the largest Lyapunov exponent of `field` along `reference`.
+///
+/// `reference` is first advanced `transient` steps, unaccompanied, so the
+/// estimate is taken on the attractor rather than during the approach to it.
+/// From there `reference` evolves alongside a shadow trajectory initially
+/// separated by `LYAPUNOV_D0`. After each step the new separation `d1`
+/// contributes `ln(d1 / LYAPUNOV_D0)` to a running sum, and the shadow is
+/// renormalized back to distance `LYAPUNOV_D0` along the current separation
+/// direction so it keeps tracking the local expansion rate rather than
+/// diverging. The exponent is `(1 / (steps*dt)) * sum(ln(d1 / d0))`.
+fn estimate_lyapunov(
+    field: &dyn VectorField,
+    reference: Vec3s,
+    dt: f64,
+    scheme: &Scheme,
+    transient: usize,
+    steps: usize,
+) -> f64 {
+    let mut reference = reference;
+    for _ in 0..transient {
+        reference = integrate(field, &reference, dt, scheme);
+    }
+    let mut shadow = reference.clone();
+    shadow[0].x += LYAPUNOV_D0;
+
+    let mut sum_log_ratio = 0.0;
+    for step in 1..=steps {
+        reference = integrate(field, &reference, dt, scheme);
+        shadow = integrate(field, &shadow, dt, scheme);
+
+        let separation = sub_all(&shadow, &reference);
+        let d1 = norm_all(&separation);
+        sum_log_ratio += (d1 / LYAPUNOV_D0).ln();
+        shadow = add_all(&reference, &scale_all(&separation, LYAPUNOV_D0 / d1));
+
+        if step % 1000 == 0 {
+            eprintln!(
+                "step {}: running lambda1 ~ {:.6}",
+                step,
+                sum_log_ratio / (step as f64 * dt)
+            );
+        }
+    }
+    sum_log_ratio / (steps as f64 * dt)
+}
+
+/// Small seeded PRNG (splitmix64) used to keep stochastic runs reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// Next raw 64-bit output, advancing the generator state.
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform draw in `(0, 1]`, avoiding 0 so it is safe to feed into `ln`.
+    fn next_uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 1.0) / ((1u64 << 53) as f64 + 1.0)
+    }
+
+    /// Standard-normal draw via the Box-Muller transform.
+    fn next_standard_normal(&mut self) -> f64 {
+        let u1 = self.next_uniform();
+        let u2 = self.next_uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    /// One independent standard-normal draw per coordinate, per `Vec3`.
+    fn next_standard_normal_vec3s(&mut self, n: usize) -> Vec3s {
+        (0..n)
+            .map(|_| Vec3 {
+                x: self.next_standard_normal(),
+                y: self.next_standard_normal(),
+                z: self.next_standard_normal(),
+            })
+            .collect()
+    }
+}
+
+/// Advance `y` by one Euler-Maruyama step under drift `f` and diffusion `g`.
+///
+/// Computes `y + f(y)*dt + g(y)*sqrt(dt)*Z`, with `Z` an independent
+/// standard-normal draw per component drawn from `rng`.
+fn step_euler_maruyama(
+    y: &[Vec3],
+    f: &dyn VectorField,
+    g: &dyn VectorField,
+    dt: f64,
+    rng: &mut Rng,
+) -> Vec3s {
+    let drift = scale_all(&f.derivative(y), dt);
+    let noise = rng.next_standard_normal_vec3s(y.len());
+    let diffusion: Vec3s = g
+        .derivative(y)
+        .iter()
+        .zip(&noise)
+        .map(|(&amplitude, &z)| amplitude.hadamard(z) * dt.sqrt())
+        .collect();
+    add_all(&add_all(y, &drift), &diffusion)
+}
+
+/// Streams `series,t,x,y,z` trajectory rows to a `Write` sink, kept separate
+/// from the human-readable banners so piping the sink to a file yields a
+/// clean, parseable table. The `series` column identifies which run a row
+/// belongs to, since multiple writers may share one sink (e.g. stdout)
+/// back-to-back and would otherwise be indistinguishable downstream.
+struct TrajectoryWriter<W: Write> {
+    sink: W,
+    delimiter: char,
+    thin: usize,
+    step: usize,
+    series: &'static str,
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    /// `series` tags every row this writer emits. `thin` emits every
+    /// `thin`-th row (1 = every row). `header` writes a `series,t,x,y,z`
+    /// column header as the first row.
+    fn new(
+        mut sink: W,
+        delimiter: char,
+        header: bool,
+        thin: usize,
+        series: &'static str,
+    ) -> io::Result<Self> {
+        if header {
+            let d = delimiter;
+            writeln!(sink, "series{d}t{d}x{d}y{d}z")?;
+        }
+        Ok(TrajectoryWriter { sink, delimiter, thin: thin.max(1), step: 0, series })
+    }
+
+    /// Write one `(t, state)` row for the first body in `state`, thinned to
+    /// every `thin`-th call.
+    fn write_row(&mut self, t: f64, state: &[Vec3]) -> io::Result<()> {
+        let step = self.step;
+        self.step += 1;
+        if !step.is_multiple_of(self.thin) {
+            return Ok(());
+        }
+        let d = self.delimiter;
+        let series = self.series;
+        let Vec3 { x, y, z } = state[0];
+        writeln!(self.sink, "{series}{d}{t}{d}{x}{d}{y}{d}{z}")
+    }
+}
+
+fn main() -> io::Result<()> {
+    let lorenz = Lorenz63 { rho: 28.0, sigma: 10.0, beta: 8.0 / 3.0 };
+    let scheme = Scheme::Rk4;
+    let dt = 1e-3; // time step
+
+    eprintln!("Lorenz 63 model");
+    let mut state = vec![Vec3 { x: 1.0, y: 1.0, z: 1.0 }]; // position
+    eprintln!("The initial state is at {:?}", state[0]);
+
+    let mut writer = TrajectoryWriter::new(io::stdout(), ',', true, 1, "lorenz_rk4")?;
     for i in 1..100 {
-        dx = SIGMA * (y - x);
-        dy = x * (RHO - z) - y;
-        dz = x * y - BETA * z;
-        x += dt * dx;
-        y += dt * dy;
-        z += dt * dz;
-        println!("t = {:.3}, [{:.8}, {:.8}, {:.8}]", i as f64 * dt, x, y, z)
+        state = integrate(&lorenz, &state, dt, &scheme);
+        writer.write_row(i as f64 * dt, &state)?;
     }
+
+    eprintln!("\nEuler vs. RK4 after the same 100 steps");
+    let mut euler_state = vec![Vec3 { x: 1.0, y: 1.0, z: 1.0 }];
+    for _ in 1..100 {
+        euler_state = integrate(&lorenz, &euler_state, dt, &Scheme::Euler);
+    }
+    eprintln!("Euler: {:?}", euler_state[0]);
+    eprintln!("RK4:   {:?}", state[0]);
+
+    eprintln!("\nStochastic Lorenz 63 model (Euler-Maruyama, seed = 0)");
+    let noise = ConstantNoise { amplitude: 1.0 };
+    let mut rng = Rng::new(0);
+    let mut noisy_state = vec![Vec3 { x: 1.0, y: 1.0, z: 1.0 }];
+    // Thinned to every 10th step, with no repeated header in the same stream;
+    // the "series" column is what keeps these rows apart from `writer`'s.
+    let mut noisy_writer = TrajectoryWriter::new(io::stdout(), ',', false, 10, "lorenz_em_noisy")?;
+    for i in 1..100 {
+        noisy_state = step_euler_maruyama(&noisy_state, &lorenz, &noise, dt, &mut rng);
+        noisy_writer.write_row(i as f64 * dt, &noisy_state)?;
+    }
+
+    eprintln!("\nEnsemble of 1000 perturbed Lorenz trajectories (rayon, 200 steps)");
+    let members = 1000;
+    let mut ensemble: Vec<Vec3s> = (0..members)
+        .map(|i| vec![Vec3 { x: 1.0 + 1e-6 * i as f64, y: 1.0, z: 1.0 }])
+        .collect();
+    for _ in 0..200 {
+        integrate_ensemble(&lorenz, &mut ensemble, dt, &scheme);
+    }
+    eprintln!("member 0 final state: {:?}", ensemble[0][0]);
+    eprintln!("member {} final state: {:?}", members - 1, ensemble[members - 1][0]);
+
+    eprintln!("\nFinite-time largest Lyapunov exponent estimate for Lorenz 63");
+    let lambda1 = estimate_lyapunov(
+        &lorenz,
+        vec![Vec3 { x: 1.0, y: 1.0, z: 1.0 }],
+        dt,
+        &scheme,
+        10_000,
+        100_000,
+    );
+    eprintln!("lambda1 ~ {:.6}", lambda1);
+
+    eprintln!("\nThree-body gravitational system (Lagrange equilateral-triangle orbit, same RK4 integrator)");
+    let three_body = NBody { g: 1.0, masses: vec![1.0, 1.0, 1.0] };
+    // Lagrange's equilateral-triangle solution: equal masses at the vertices
+    // of an equilateral triangle of side `side`, orbiting their common
+    // center of mass at angular velocity omega, where omega^2 = G*(total
+    // mass)/side^3. Unlike a cold start, this actually orbits instead of
+    // free-falling together.
+    let radius = 1.0;
+    let side = radius * 3.0_f64.sqrt();
+    let total_mass: f64 = three_body.masses.iter().sum();
+    let omega = (three_body.g * total_mass / side.powi(3)).sqrt();
+    let angle = |k: usize| 2.0 * std::f64::consts::PI * k as f64 / 3.0;
+    let mut bodies: Vec3s = (0..3)
+        .map(|k| Vec3 { x: radius * angle(k).cos(), y: radius * angle(k).sin(), z: 0.0 })
+        .collect();
+    bodies.extend((0..3).map(|k| Vec3 {
+        x: -omega * radius * angle(k).sin(),
+        y: omega * radius * angle(k).cos(),
+        z: 0.0,
+    }));
+    for i in 1..100 {
+        bodies = integrate(&three_body, &bodies, dt, &scheme);
+        eprintln!("t = {:.3}, {:?}", i as f64 * dt, &bodies[..3]);
+    }
+
+    Ok(())
 }